@@ -0,0 +1,202 @@
+//! CPFP-correct block template assembly: given the current mempool's
+//! fee/size/dependency data, pick an ordered, dependency-valid set of txids
+//! that approximately maximizes fee within a weight budget, using the
+//! ancestor-package greedy algorithm block-building software uses.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Per-transaction fee/size/dependency data needed to build a template,
+/// independent of `Mempool`'s storage so the algorithm can be unit tested
+/// against a plain `HashMap`.
+#[derive(Clone)]
+pub struct TxMeta {
+    pub fee_sat: u64,
+    pub vsize: u64,
+    pub weight: u64,
+    pub depends: Vec<String>,
+}
+
+/// A candidate package in the max-heap, scored by ancestorFee/ancestorSize.
+/// Scores are recomputed lazily: by the time a package is popped some of its
+/// ancestors may already be included by an earlier, better-ranked package, so
+/// the entry is re-scored on pop and re-pushed if it changed.
+struct PackageCandidate {
+    txid: String,
+    ancestor_fee_sat: u64,
+    ancestor_vsize: u64,
+}
+
+impl PackageCandidate {
+    fn fee_rate(&self) -> f64 {
+        self.ancestor_fee_sat as f64 / self.ancestor_vsize.max(1) as f64
+    }
+}
+
+impl PartialEq for PackageCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.fee_rate() == other.fee_rate()
+    }
+}
+
+impl Eq for PackageCandidate {}
+
+impl PartialOrd for PackageCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PackageCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.fee_rate()
+            .partial_cmp(&other.fee_rate())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Walks `depends` from `txid` up to the root(s), memoizing per-txid results
+/// so shared ancestors aren't re-walked for every descendant.
+fn ancestors_of(
+    txid: &str,
+    entries: &HashMap<String, TxMeta>,
+    cache: &mut HashMap<String, HashSet<String>>,
+) -> HashSet<String> {
+    if let Some(cached) = cache.get(txid) {
+        return cached.clone();
+    }
+    let mut ancestors = HashSet::new();
+    if let Some(meta) = entries.get(txid) {
+        for parent in &meta.depends {
+            // Skip depends pointing at a parent no longer in the mempool (confirmed).
+            if !entries.contains_key(parent) {
+                continue;
+            }
+            ancestors.insert(parent.clone());
+            for grandparent in ancestors_of(parent, entries, cache) {
+                ancestors.insert(grandparent);
+            }
+        }
+    }
+    cache.insert(txid.to_string(), ancestors.clone());
+    ancestors
+}
+
+/// Sums fee/vsize/weight of `txid` plus the given (already-deduped) ancestor set.
+fn sum_package(
+    txid: &str,
+    ancestors: &HashSet<String>,
+    entries: &HashMap<String, TxMeta>,
+) -> (u64, u64, u64) {
+    let mut fee_sat = 0u64;
+    let mut vsize = 0u64;
+    let mut weight = 0u64;
+    for member in ancestors.iter().chain(std::iter::once(&txid.to_string())) {
+        if let Some(meta) = entries.get(member) {
+            fee_sat += meta.fee_sat;
+            vsize += meta.vsize;
+            weight += meta.weight;
+        }
+    }
+    (fee_sat, vsize, weight)
+}
+
+/// Orders `members` parents-before-children using each tx's `depends` list,
+/// restricted to `members` (ancestors outside the set are already included).
+fn topological_order(members: &HashSet<String>, entries: &HashMap<String, TxMeta>) -> Vec<String> {
+    let mut ordered = Vec::with_capacity(members.len());
+    let mut visited = HashSet::new();
+
+    fn visit(
+        txid: &str,
+        members: &HashSet<String>,
+        entries: &HashMap<String, TxMeta>,
+        visited: &mut HashSet<String>,
+        ordered: &mut Vec<String>,
+    ) {
+        // Skip txids no longer in the mempool (confirmed).
+        let Some(meta) = entries.get(txid) else {
+            return;
+        };
+        if !visited.insert(txid.to_string()) {
+            return;
+        }
+        for parent in &meta.depends {
+            if members.contains(parent) {
+                visit(parent, members, entries, visited, ordered);
+            }
+        }
+        ordered.push(txid.to_string());
+    }
+
+    for txid in members {
+        visit(txid, members, entries, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+/// Greedily selects the highest ancestor-fee-rate packages that fit within
+/// `max_weight`, returning an ordered, dependency-valid list of txids
+/// (parents always precede children, and a transaction's whole unincluded
+/// ancestor set is emitted alongside it).
+pub fn build_template(entries: &HashMap<String, TxMeta>, max_weight: u64) -> Vec<String> {
+    let mut ancestor_cache: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut heap = BinaryHeap::with_capacity(entries.len());
+
+    for txid in entries.keys() {
+        let ancestors = ancestors_of(txid, entries, &mut ancestor_cache);
+        let (fee_sat, vsize, _) = sum_package(txid, &ancestors, entries);
+        heap.push(PackageCandidate {
+            txid: txid.clone(),
+            ancestor_fee_sat: fee_sat,
+            ancestor_vsize: vsize,
+        });
+    }
+
+    let mut included: HashSet<String> = HashSet::new();
+    let mut remaining_weight = max_weight;
+    let mut result = Vec::new();
+
+    while let Some(top) = heap.pop() {
+        if included.contains(&top.txid) {
+            continue;
+        }
+
+        let ancestors = ancestors_of(&top.txid, entries, &mut ancestor_cache);
+        let pending_ancestors: HashSet<String> = ancestors
+            .into_iter()
+            .filter(|a| !included.contains(a))
+            .collect();
+        let (fee_sat, vsize, weight) = sum_package(&top.txid, &pending_ancestors, entries);
+
+        // Already-included ancestors no longer count against this package, so
+        // its effective fee-rate may have changed (up or down) since it was
+        // pushed; re-push it with the new score rather than selecting it now.
+        if fee_sat != top.ancestor_fee_sat || vsize != top.ancestor_vsize {
+            heap.push(PackageCandidate {
+                txid: top.txid,
+                ancestor_fee_sat: fee_sat,
+                ancestor_vsize: vsize,
+            });
+            continue;
+        }
+
+        if weight > remaining_weight {
+            // This package doesn't fit the remaining budget, but a smaller,
+            // cheaper one further down the heap still might; it was already
+            // popped off the heap above so it won't be retried. Skip it
+            // rather than stopping the whole template early.
+            continue;
+        }
+
+        for parent in topological_order(&pending_ancestors, entries) {
+            included.insert(parent.clone());
+            result.push(parent);
+        }
+        included.insert(top.txid.clone());
+        result.push(top.txid);
+        remaining_weight -= weight;
+    }
+
+    result
+}
@@ -1,29 +1,36 @@
 use anyhow::{anyhow, Context, Result};
 use bitcoincore_rpc::bitcoin::BlockHash;
-use bitcoincore_rpc::{bitcoin::hashes::sha256d::Hash, bitcoin::Txid, Auth, Client, RpcApi};
+use bitcoincore_rpc::{
+    bitcoin::hashes::sha256d::Hash, bitcoin::Txid, jsonrpc, Auth, Client, RpcApi,
+};
 use bitcoincore_zmqsequence::check::{ClientConfig, NodeChecker};
 use bitcoincore_zmqsequence::{MempoolSequence, ZmqSeqListener};
+use blocktemplate::build_template;
 use log::{error, info, log, warn, Level, LevelFilter};
-use mempool::Mempool;
+use mempool::{Mempool, MempoolStats};
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::getpid;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rocket::response::stream::{ByteStream, TextStream};
+use rocket::response::stream::{ByteStream, Event, EventStream, TextStream};
 // use rocket::tokio::runtime::Handle;
+use rocket::serde::json::Json;
 use rocket::State;
+use serde::Serialize;
 use settings::{BitcoindClient, Settings};
 use simple_logger::SimpleLogger;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 use txdepth::TxDepth;
 use url::Url;
 
+mod blocktemplate;
 mod mempool;
 mod settings;
 mod txdepth;
@@ -31,12 +38,19 @@ mod txdepth;
 #[macro_use]
 extern crate rocket;
 
+/// `/health` reports degraded once this long has passed without a ZMQ
+/// mempool-sequence message, since that's the filler thread's only signal
+/// that it's still in sync with the node.
+const ZMQ_STALE_AFTER_SECS: u64 = 120;
+
 struct App {
     pub mempool: Arc<Mempool>,
     pub zmqseqlistener_stop: Arc<AtomicBool>,
     pub zmqseqlistener_thread: JoinHandle<()>,
     pub mp_filler_stop: Arc<AtomicBool>,
     pub mp_filler_thread: JoinHandle<()>,
+    pub health_bcc: Arc<Client>,
+    pub zmq_last_seen_unix: Arc<AtomicU64>,
 }
 
 #[rocket::main]
@@ -46,9 +60,21 @@ async fn main() -> Result<(), rocket::Error> {
             info!("Mempool data loaded, launching REST Server...");
             rocket::build()
                 .manage(app.mempool)
+                .manage(app.health_bcc)
+                .manage(app.zmq_last_seen_unix)
                 .mount(
                     "/mempoolServer",
-                    routes![size, txsids, txsdata, txsdatafrom],
+                    routes![
+                        size,
+                        stats,
+                        txsids,
+                        txsdata,
+                        txsdatafrom,
+                        txsdata_minfeerate,
+                        events,
+                        blocktemplate,
+                        health
+                    ],
                 )
                 .launch()
                 .await?;
@@ -100,9 +126,10 @@ fn main_app() -> Result<App> {
     ))?;
     let zmqseqlistener = ZmqSeqListener::start(&zmq_url)?;
     let bcc = get_client(&settings.bitcoind_client)?;
-    let size = log_mempool_size(&bcc, Level::Info)?;
+    log_mempool_size(&bcc, Level::Info)?;
+    let jsonrpc_client = get_jsonrpc_client(&settings.bitcoind_client)?;
 
-    let vec = get_tx_dept_vec(&bcc, size)?;
+    let vec = get_tx_dept_vec(&bcc, &jsonrpc_client)?;
     //vec2 is a vector of vectors containing txs with same ancestor_count:
     //(vec2[ancestor_count-1] has a vector with all tx having ancestor_count-1)
     let vec2 = get_mempool_layers(vec);
@@ -112,6 +139,9 @@ fn main_app() -> Result<App> {
     mempool.load_mempool_with(vec2);
     info!("Loaded mempool with {} transactions", mempool.len());
 
+    let zmq_last_seen_unix = Arc::new(AtomicU64::new(now_unix_secs()));
+    let health_bcc = Arc::new(get_client(&settings.bitcoind_client)?);
+
     let mp_filler_stop_th = Arc::new(AtomicBool::new(false));
     let mp_filler_thread = launch_mp_filler_thread(
         mp_filler_stop_th.clone(),
@@ -119,6 +149,7 @@ fn main_app() -> Result<App> {
         mempool.clone(),
         bcc,
         settings.bitcoind_client.wait_timeout_sec.unwrap(),
+        zmq_last_seen_unix.clone(),
     );
 
     Ok(App {
@@ -127,15 +158,25 @@ fn main_app() -> Result<App> {
         zmqseqlistener_thread: zmqseqlistener.thread,
         mp_filler_stop: mp_filler_stop_th,
         mp_filler_thread,
+        health_bcc,
+        zmq_last_seen_unix,
     })
 }
 
+fn now_unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 fn launch_mp_filler_thread(
     stop_th2: Arc<AtomicBool>,
     rx: Receiver<MempoolSequence>,
     mempool: Arc<Mempool>,
     bcc: Client,
     timeout_sec: u64,
+    zmq_last_seen_unix: Arc<AtomicU64>,
 ) -> JoinHandle<()> {
     let thread = thread::Builder::new()
         .name(String::from("mp_filler"))
@@ -145,6 +186,7 @@ fn launch_mp_filler_thread(
                 match rx.recv_timeout(Duration::from_secs(timeout_sec)) {
                     Ok(mps) => {
                         debug!("{:?}", &mps);
+                        zmq_last_seen_unix.store(now_unix_secs(), Ordering::SeqCst);
                         update_mempool(&mempool, &mps, &bcc).unwrap();
                         debug!(
                             "Mempool size: {}, mempool counter: {}",
@@ -225,6 +267,20 @@ fn get_client_user_passw(ip: &str, user_name: String, passwd: String) -> Result<
         .with_context(|| format!("Can't connect to bitcoind node: {}", ip))
 }
 
+/// Mirrors `get_client`'s auth handling, but builds a raw `jsonrpc::Client` so
+/// `get_tx_dept_vec` can issue `getrawtransaction` batch requests, which
+/// `bitcoincore_rpc::Client` doesn't expose.
+fn get_jsonrpc_client(bcc: &BitcoindClient) -> Result<jsonrpc::Client> {
+    let (user, passwd) = match &bcc.cookie_auth_path {
+        Some(path) => Auth::CookieFile(path.clone())
+            .get_user_pass()
+            .with_context(|| format!("Can't read cookie file: {}", path.display()))?,
+        None => (bcc.user.clone(), bcc.passwd.clone()),
+    };
+    jsonrpc::Client::simple_http(&bcc.ip_addr, user, passwd)
+        .with_context(|| format!("Can't connect to bitcoind node: {}", bcc.ip_addr))
+}
+
 fn log_mempool_size(bcc: &Client, level: Level) -> Result<usize, anyhow::Error> {
     let size = bcc
         .get_mempool_info()
@@ -234,47 +290,118 @@ fn log_mempool_size(bcc: &Client, level: Level) -> Result<usize, anyhow::Error>
     Ok(size)
 }
 
-fn get_tx_dept_vec(source_client: &Client, size: usize) -> Result<Vec<TxDepth>> {
+/// Transactions per `getrawtransaction` batch request. Large enough to cut
+/// round trips by orders of magnitude on a big mempool, small enough that one
+/// slow/failing batch doesn't throw away too much progress.
+const BATCH_SIZE: usize = 750;
+
+fn get_tx_dept_vec(
+    source_client: &Client,
+    jsonrpc_client: &jsonrpc::Client,
+) -> Result<Vec<TxDepth>> {
     info!("Loading mempool txids and hierarchy...");
-    let i = AtomicU32::new(0);
-    let last_per = AtomicU32::new(0);
-    let vec: Vec<TxDepth> = source_client
-        .get_raw_mempool_verbose()?
+    let mempool_entries = source_client.get_raw_mempool_verbose()?;
+    let txids: Vec<Txid> = mempool_entries.keys().cloned().collect();
+    let chunks: Vec<&[Txid]> = txids.chunks(BATCH_SIZE).collect();
+    let total_batches = chunks.len();
+    info!(
+        "Mempool txids and hierarchy loaded, now asking full txs binary data in {} batches of up to {} txs...",
+        total_batches, BATCH_SIZE
+    );
+    let completed_batches = AtomicU32::new(0);
+
+    let vec: Vec<TxDepth> = chunks
         .par_iter()
-        .map(|(txid, mpe)| {
-            percent(&i, &last_per, size as u32);
-            (txid, mpe)
-        })
-        .filter_map(|(tx_ide, mempool_entry)| {
-            match source_client.get_raw_transaction_hex(tx_ide, None) {
-                Ok(raw) => Some(TxDepth {
-                    ancestor_count: mempool_entry.ancestor_count as usize,
-                    tx_id: tx_ide.clone(),
-                    bytes: hex::decode(raw).unwrap(),
-                }),
-                Err(_) => None, //If tx_id do not exist we don't care
-            }
+        .flat_map(|chunk| {
+            let hexes = batch_get_raw_tx_hex_with_fallback(source_client, jsonrpc_client, chunk);
+            let done = completed_batches.fetch_add(1, Ordering::SeqCst) + 1;
+            let per = (done as f32 / total_batches.max(1) as f32 * 100f32).trunc() as u32;
+            info!("Loaded batch {}/{}: {}%", done, total_batches, per);
+
+            hexes
+                .into_iter()
+                .filter_map(|(txid, hex)| {
+                    let raw = hex?; //Evicted between the verbose dump and the batch fetch, we don't care
+                    let mempool_entry = mempool_entries.get(&txid)?;
+                    Some(TxDepth {
+                        ancestor_count: mempool_entry.ancestor_count as usize,
+                        tx_id: txid,
+                        bytes: hex::decode(raw).ok()?,
+                        vsize: mempool_entry.vsize,
+                        weight: mempool_entry.weight.unwrap_or(mempool_entry.vsize * 4),
+                        fee_sat: mempool_entry.fees.base.to_sat(),
+                        depends: mempool_entry.depends.clone(),
+                    })
+                })
+                .collect::<Vec<_>>()
         })
         .collect();
-    return Ok(vec);
+    Ok(vec)
 }
 
-//This funcion is incorrect, but the worst can happen (very unlikely) is a % been skipped.
-fn percent(ai: &AtomicU32, alast_per: &AtomicU32, size: u32) {
-    let i = ai.fetch_add(1, Ordering::SeqCst);
-    if i == 0 || size == 0 {
-        info!("Mempool txids and hierarchy loaded, now asking full txs binary data...");
-        info!("Loading: 0%");
-    } else {
-        if i == size {
-            info!("Done: 100%");
-        } else {
-            let per = ((i as f32 / size as f32) * 100f32).trunc() as u32;
-            if alast_per.fetch_max(per, Ordering::SeqCst) != per {
-                info!("Loading: {}%", per);
-            }
+/// Fetches `getrawtransaction` for every txid in `txids` as a single
+/// JSON-RPC batch request, returning `None` per-txid for entries that
+/// errored (e.g. evicted between the verbose mempool dump and this call).
+fn batch_get_raw_tx_hex(
+    jsonrpc_client: &jsonrpc::Client,
+    txids: &[Txid],
+) -> Result<Vec<(Txid, Option<String>)>> {
+    let params: Vec<Box<serde_json::value::RawValue>> = txids
+        .iter()
+        .map(|txid| serde_json::value::to_raw_value(&txid.to_string()))
+        .collect::<std::result::Result<_, _>>()
+        .context("Can't serialize getrawtransaction batch params")?;
+
+    let requests: Vec<jsonrpc::Request> = params
+        .iter()
+        .map(|p| jsonrpc_client.build_request("getrawtransaction", std::slice::from_ref(p)))
+        .collect();
+
+    let responses = jsonrpc_client
+        .send_batch(&requests)
+        .context("Batched getrawtransaction RPC call failed")?;
+
+    Ok(txids
+        .iter()
+        .cloned()
+        .zip(responses)
+        .map(|(txid, resp)| (txid, resp.and_then(|r| r.result::<String>().ok())))
+        .collect())
+}
+
+/// Retries `batch_get_raw_tx_hex` once, then falls back to per-tx fetch via
+/// `source_client` rather than dropping the whole batch.
+fn batch_get_raw_tx_hex_with_fallback(
+    source_client: &Client,
+    jsonrpc_client: &jsonrpc::Client,
+    txids: &[Txid],
+) -> Vec<(Txid, Option<String>)> {
+    for attempt in 1..=2 {
+        match batch_get_raw_tx_hex(jsonrpc_client, txids) {
+            Ok(hexes) => return hexes,
+            Err(e) => warn!(
+                "Batched getrawtransaction RPC call failed (attempt {}/2): {}",
+                attempt, e
+            ),
         }
     }
+    error!(
+        "Batched getrawtransaction RPC call failed twice, falling back to per-tx fetch for this batch of {} txs",
+        txids.len()
+    );
+    txids
+        .iter()
+        .map(|&txid| {
+            let hex = match source_client.get_raw_transaction_hex(&txid, None) {
+                Ok(hex) => Some(hex),
+                Err(e) => {
+                    info!("tx_id: {} not found, err{}", txid, e);
+                    None
+                }
+            };
+            (txid, hex)
+        })
+        .collect()
 }
 
 fn get_mempool_layers(vec: Vec<TxDepth>) -> Vec<Vec<TxDepth>> {
@@ -308,6 +435,30 @@ fn get_raw_transaction_hex(bcc: &Client, tx_id: &Txid) -> Option<Vec<u8>> {
     }
 }
 
+/// Fetches a tx's bytes together with the vsize/weight/fee it still has in the
+/// mempool, so a newly seen tx can be stored with the same per-tx stats the
+/// initial load computes. Returns `None` if the tx is gone by the time we ask
+/// (evicted/confirmed between the ZMQ notification and this lookup).
+fn get_raw_transaction_with_fee_data(
+    bcc: &Client,
+    tx_id: &Txid,
+) -> Option<(Vec<u8>, u64, u64, u64, Vec<Txid>)> {
+    let bytes = get_raw_transaction_hex(bcc, tx_id)?;
+    match bcc.get_mempool_entry(tx_id) {
+        Ok(mempool_entry) => Some((
+            bytes,
+            mempool_entry.vsize,
+            mempool_entry.weight.unwrap_or(mempool_entry.vsize * 4),
+            mempool_entry.fees.base.to_sat(),
+            mempool_entry.depends,
+        )),
+        Err(e) => {
+            info!("tx_id: {} not found in mempool entry, err{}", tx_id, e);
+            None
+        }
+    }
+}
+
 fn update_mempool(mempool: &Mempool, mps: &MempoolSequence, bcc: &Client) -> Result<()> {
     match mps {
         MempoolSequence::SeqStart {
@@ -322,8 +473,11 @@ fn update_mempool(mempool: &Mempool, mps: &MempoolSequence, bcc: &Client) -> Res
         MempoolSequence::SeqError { error } => Err(anyhow!("Error: {}", error)),
         MempoolSequence::TxAdded { txid, .. } => {
             let tx_id = &Txid::from(Hash::from_str(txid.as_str())?);
-            if let Some(bytes) = get_raw_transaction_hex(bcc, tx_id) {
-                mempool.add_tx(txid.clone(), bytes);
+            if let Some((bytes, vsize, weight, fee_sat, depends)) =
+                get_raw_transaction_with_fee_data(bcc, tx_id)
+            {
+                let depends = depends.iter().map(|txid| txid.to_string()).collect();
+                mempool.add_tx(txid.clone(), bytes, vsize, weight, fee_sat, depends);
             }
             Ok(())
         }
@@ -341,8 +495,11 @@ fn update_mempool(mempool: &Mempool, mps: &MempoolSequence, bcc: &Client) -> Res
         MempoolSequence::BlockDisconnection { block_hash, .. } => {
             let block = bcc.get_block_info(&BlockHash::from_str(&block_hash)?)?;
             block.tx.iter().for_each(|tx_id| {
-                if let Some(bytes) = get_raw_transaction_hex(bcc, tx_id) {
-                    mempool.add_tx(tx_id.to_string(), bytes);
+                if let Some((bytes, vsize, weight, fee_sat, depends)) =
+                    get_raw_transaction_with_fee_data(bcc, tx_id)
+                {
+                    let depends = depends.iter().map(|txid| txid.to_string()).collect();
+                    mempool.add_tx(tx_id.to_string(), bytes, vsize, weight, fee_sat, depends);
                 }
             });
             Ok(())
@@ -355,6 +512,28 @@ fn size(mempool: &State<Arc<Mempool>>) -> String {
     format!("{}", mempool.len())
 }
 
+#[get("/stats")]
+fn stats(mempool: &State<Arc<Mempool>>) -> Json<MempoolStats> {
+    Json(mempool.stats())
+}
+
+/// Pushes add/remove deltas to connected clients as they happen, so a
+/// consumer can mirror the mempool instead of repeatedly polling
+/// `/txsdatafrom/<from>`.
+#[get("/events")]
+fn events(mempool: &State<Arc<Mempool>>) -> EventStream![] {
+    let mut rx = mempool.subscribe();
+    EventStream! {
+        loop {
+            match rx.recv().await {
+                Ok(event) => yield Event::json(&event),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
 #[get("/txsids")]
 fn txsids(mempool: &State<Arc<Mempool>>) -> TextStream![String + '_] {
     TextStream! {
@@ -370,7 +549,7 @@ fn txsdata(mempool: &State<Arc<Mempool>>) -> ByteStream![Vec<u8> + '_] {
     ByteStream! {
         info!("Started stream.");
         for entry in mempool.pos_data_iterator(){
-            let data = entry.value().clone();
+            let data = entry.value().bytes.clone();
             let size = data.len() as u32;
             if first {
                 first=false;
@@ -391,7 +570,7 @@ fn txsdatafrom(from: u64, mempool: &State<Arc<Mempool>>) -> ByteStream![Vec<u8>
     ByteStream! {
     let range = mempool.pos_data_iterator_from(from);
         for entry in range{
-            let data = entry.value().clone();
+            let data = entry.value().bytes.clone();
             let size = data.len() as u32;
             if first {
                 first=false;
@@ -404,3 +583,121 @@ fn txsdatafrom(from: u64, mempool: &State<Arc<Mempool>>) -> ByteStream![Vec<u8>
         }
     }
 }
+
+#[derive(Serialize)]
+struct HealthStatus {
+    status: &'static str,
+    block_height: u64,
+    verification_progress: f64,
+    initial_block_download: bool,
+    peer_count: usize,
+    zmq_seconds_since_last_message: u64,
+}
+
+/// Reports block height, sync state, connected peer count and ZMQ liveness,
+/// with an overall ok/degraded status, so orchestration and
+/// load balancers can decide whether this instance's mempool view is
+/// trustworthy before routing stream requests to it.
+#[get("/health")]
+async fn health(
+    bcc: &State<Arc<Client>>,
+    zmq_last_seen_unix: &State<Arc<AtomicU64>>,
+) -> Json<HealthStatus> {
+    let bcc = bcc.inner().clone();
+    let (blockchain_info, network_info) = rocket::tokio::task::spawn_blocking(move || {
+        (bcc.get_blockchain_info(), bcc.get_network_info())
+    })
+    .await
+    .expect("health RPC task panicked");
+    let zmq_seconds_since_last_message =
+        now_unix_secs().saturating_sub(zmq_last_seen_unix.load(Ordering::SeqCst));
+
+    let (block_height, verification_progress, initial_block_download) = match &blockchain_info {
+        Ok(info) => (
+            info.blocks,
+            info.verification_progress,
+            info.initial_block_download,
+        ),
+        Err(_) => (0, 0.0, true),
+    };
+    let peer_count = network_info
+        .as_ref()
+        .map(|info| info.connections)
+        .unwrap_or(0);
+
+    let ok = blockchain_info.is_ok()
+        && network_info.is_ok()
+        && !initial_block_download
+        && peer_count > 0
+        && zmq_seconds_since_last_message < ZMQ_STALE_AFTER_SECS;
+
+    Json(HealthStatus {
+        status: if ok { "ok" } else { "degraded" },
+        block_height,
+        verification_progress,
+        initial_block_download,
+        peer_count,
+        zmq_seconds_since_last_message,
+    })
+}
+
+#[derive(Serialize)]
+struct TemplateTx {
+    txid: String,
+    bytes: Option<String>,
+}
+
+/// Returns an ordered, dependency-valid list of txids (parents before
+/// children) selected by the ancestor-package greedy algorithm to
+/// approximately maximize fee within `maxweight`, giving CPFP-correct
+/// ordering rather than the naive ancestor-count layering used internally to
+/// stream transactions to a node. Pass `withbytes=true` to also include each
+/// transaction's raw bytes.
+#[get("/blocktemplate?<maxweight>&<withbytes>")]
+fn blocktemplate(
+    maxweight: u64,
+    withbytes: Option<bool>,
+    mempool: &State<Arc<Mempool>>,
+) -> Json<Vec<TemplateTx>> {
+    let entries = mempool.template_snapshot();
+    let withbytes = withbytes.unwrap_or(false);
+    let template = build_template(&entries, maxweight)
+        .into_iter()
+        .map(|txid| {
+            let bytes = if withbytes {
+                mempool.get_bytes(&txid).map(hex::encode)
+            } else {
+                None
+            };
+            TemplateTx { txid, bytes }
+        })
+        .collect();
+    Json(template)
+}
+
+/// Like `txsdata`, but only streams transactions whose own fee rate meets or
+/// exceeds `min_sat_per_vb`, so a client that only cares about the
+/// economically relevant tail of the mempool doesn't have to download (and
+/// filter out) every dust-rate transaction itself.
+#[get("/txsdata/minfeerate/<min_sat_per_vb>")]
+fn txsdata_minfeerate(
+    min_sat_per_vb: f64,
+    mempool: &State<Arc<Mempool>>,
+) -> ByteStream![Vec<u8> + '_] {
+    let mut first = true;
+    ByteStream! {
+        //No hint of its size since filtering makes it unknown ahead of time
+        let range = mempool.pos_data_iterator().filter(|entry| entry.value().fee_rate_sat_per_vb() >= min_sat_per_vb);
+        for entry in range{
+            let data = entry.value().bytes.clone();
+            let size = data.len() as u32;
+            if first {
+                first=false;
+                yield u64::MAX.to_be_bytes().to_vec();//Magic number to start a correct stream
+                yield mempool.counter().to_be_bytes().to_vec();//u64 mempool counter
+            }
+            yield size.to_be_bytes().to_vec();
+            yield data;
+        }
+    }
+}
@@ -3,9 +3,65 @@ use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 
 use super::TxDepth;
+use crate::blocktemplate::TxMeta;
 use crossbeam_skiplist::SkipMap;
 use dashmap::iter::Iter;
 use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+/// Capacity of the event broadcast channel. A slow subscriber that falls more
+/// than this many events behind misses some and is notified via `Lagged`
+/// rather than blocking the mempool.
+const EVENT_CHANNEL_CAPACITY: usize = 4096;
+
+/// An add/remove delta published whenever a transaction enters or leaves the
+/// mempool, so `/events` subscribers can mirror it without polling.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MempoolEvent {
+    Add { txid: String, pos: u64 },
+    Remove { txid: String, pos: u64 },
+}
+
+/// Fee-rate band edges (in sat/vB) used to bucket the `/stats` histogram.
+/// A transaction falls in the last bucket whose edge it meets or exceeds, e.g.
+/// a 5 sat/vB tx lands in the `[4,8)` bucket.
+const FEE_RATE_BUCKET_EDGES: &[u64] = &[0, 1, 2, 4, 8, 16, 32, 64, 128];
+
+/// A transaction as kept in the mempool: its raw bytes plus the fee/size data
+/// needed to answer `/stats` without re-parsing every transaction.
+#[derive(Clone)]
+pub struct TxEntry {
+    pub bytes: Vec<u8>,
+    pub vsize: u64,
+    pub weight: u64,
+    pub fee_sat: u64,
+    /// txids of this transaction's direct in-mempool parents.
+    pub depends: Vec<String>,
+}
+
+impl TxEntry {
+    pub fn fee_rate_sat_per_vb(&self) -> f64 {
+        self.fee_sat as f64 / self.vsize.max(1) as f64
+    }
+}
+
+#[derive(Serialize)]
+pub struct FeeRateBucket {
+    pub min_sat_per_vb: u64,
+    pub max_sat_per_vb: Option<u64>,
+    pub tx_count: u32,
+}
+
+#[derive(Serialize)]
+pub struct MempoolStats {
+    pub tx_count: u32,
+    pub total_vsize: u64,
+    pub total_weight: u64,
+    pub fee_rate_histogram: Vec<FeeRateBucket>,
+}
 
 /// This is an special mempool that keeps track of the order of arrival for incoming transactions.
 /// Each time a tx is added, counter is incremented.
@@ -13,23 +69,54 @@ use dashmap::DashMap;
 /// dependencies between those txs.
 pub struct Mempool {
     counter: AtomicU64,
-    id_tx_map: SkipMap<u64, Vec<u8>>,
+    id_tx_map: SkipMap<u64, TxEntry>,
     txid_id_map: DashMap<String, u64>,
+    event_tx: broadcast::Sender<MempoolEvent>,
 }
 
 impl Mempool {
     pub fn new() -> Self {
+        let (event_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
         Mempool {
             counter: AtomicU64::new(0),
             id_tx_map: SkipMap::new(),
             txid_id_map: DashMap::with_capacity(100000),
+            event_tx,
         }
     }
 
-    pub fn add_tx(&self, tx_id: String, bytes: Vec<u8>) {
+    /// Subscribes to the add/remove event stream. Intended for the `/events`
+    /// SSE route; each subscriber gets its own independent broadcast receiver.
+    pub fn subscribe(&self) -> broadcast::Receiver<MempoolEvent> {
+        self.event_tx.subscribe()
+    }
+
+    pub fn add_tx(
+        &self,
+        tx_id: String,
+        bytes: Vec<u8>,
+        vsize: u64,
+        weight: u64,
+        fee_sat: u64,
+        depends: Vec<String>,
+    ) {
         let previous_value = self.counter.fetch_add(1, Ordering::SeqCst);
-        self.txid_id_map.insert(tx_id, previous_value);
-        self.id_tx_map.insert(previous_value, bytes);
+        self.txid_id_map.insert(tx_id.clone(), previous_value);
+        self.id_tx_map.insert(
+            previous_value,
+            TxEntry {
+                bytes,
+                vsize,
+                weight,
+                fee_sat,
+                depends,
+            },
+        );
+        //No subscribers is not an error, just nobody listening right now.
+        let _ = self.event_tx.send(MempoolEvent::Add {
+            txid: tx_id,
+            pos: previous_value,
+        });
     }
 
     pub fn remove_tx(&self, tx_id: &String) {
@@ -37,6 +124,10 @@ impl Mempool {
         match kk {
             Some((_, id)) => {
                 self.id_tx_map.remove(&id);
+                let _ = self.event_tx.send(MempoolEvent::Remove {
+                    txid: tx_id.clone(),
+                    pos: id,
+                });
             }
             None => {}
         };
@@ -52,8 +143,20 @@ impl Mempool {
 
     pub fn load_mempool_with(&self, vec2: Vec<Vec<TxDepth>>) {
         vec2.into_iter().for_each(|vec| {
-            vec.into_iter()
-                .for_each(|tx_depth| self.add_tx(tx_depth.tx_id.to_string(), tx_depth.bytes))
+            vec.into_iter().for_each(|tx_depth| {
+                self.add_tx(
+                    tx_depth.tx_id.to_string(),
+                    tx_depth.bytes,
+                    tx_depth.vsize,
+                    tx_depth.weight,
+                    tx_depth.fee_sat,
+                    tx_depth
+                        .depends
+                        .iter()
+                        .map(|txid| txid.to_string())
+                        .collect(),
+                )
+            })
         });
     }
 
@@ -61,14 +164,84 @@ impl Mempool {
         self.txid_id_map.iter()
     }
 
-    pub fn pos_data_iterator(&self) -> crossbeam_skiplist::map::Iter<u64, Vec<u8>> {
+    pub fn pos_data_iterator(&self) -> crossbeam_skiplist::map::Iter<u64, TxEntry> {
         self.id_tx_map.iter()
     }
 
     pub fn pos_data_iterator_from(
         &self,
         from: u64,
-    ) -> crossbeam_skiplist::map::Range<'_, u64, RangeFrom<u64>, u64, Vec<u8>> {
+    ) -> crossbeam_skiplist::map::Range<'_, u64, RangeFrom<u64>, u64, TxEntry> {
         self.id_tx_map.range(from..)
     }
+
+    /// Aggregate transaction count, virtual size/weight and a fee-rate histogram,
+    /// mirroring the unconfirmed-tx/total-weight stats node software exposes.
+    pub fn stats(&self) -> MempoolStats {
+        let mut tx_count = 0u32;
+        let mut total_vsize = 0u64;
+        let mut total_weight = 0u64;
+        let mut bucket_counts = vec![0u32; FEE_RATE_BUCKET_EDGES.len()];
+
+        for entry in self.pos_data_iterator() {
+            let tx = entry.value();
+            tx_count += 1;
+            total_vsize += tx.vsize;
+            total_weight += tx.weight;
+
+            let fee_rate = tx.fee_rate_sat_per_vb();
+            let bucket = FEE_RATE_BUCKET_EDGES
+                .iter()
+                .rposition(|&edge| fee_rate >= edge as f64)
+                .unwrap_or(0);
+            bucket_counts[bucket] += 1;
+        }
+
+        let fee_rate_histogram = FEE_RATE_BUCKET_EDGES
+            .iter()
+            .zip(bucket_counts)
+            .enumerate()
+            .map(|(i, (&min, tx_count))| FeeRateBucket {
+                min_sat_per_vb: min,
+                max_sat_per_vb: FEE_RATE_BUCKET_EDGES.get(i + 1).copied(),
+                tx_count,
+            })
+            .collect();
+
+        MempoolStats {
+            tx_count,
+            total_vsize,
+            total_weight,
+            fee_rate_histogram,
+        }
+    }
+
+    /// A point-in-time copy of each transaction's fee/size/dependency data,
+    /// for `/blocktemplate` to run its package-selection algorithm over
+    /// without holding any mempool lock while it works.
+    pub fn template_snapshot(&self) -> HashMap<String, TxMeta> {
+        self.txid_id_map
+            .iter()
+            .filter_map(|txid_entry| {
+                let tx = self.id_tx_map.get(txid_entry.value())?;
+                let tx = tx.value();
+                Some((
+                    txid_entry.key().clone(),
+                    TxMeta {
+                        fee_sat: tx.fee_sat,
+                        vsize: tx.vsize,
+                        weight: tx.weight,
+                        depends: tx.depends.clone(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// Looks up a transaction's raw bytes by txid, for `/blocktemplate`'s
+    /// optional `withbytes` response.
+    pub fn get_bytes(&self, tx_id: &str) -> Option<Vec<u8>> {
+        let id = *self.txid_id_map.get(tx_id)?.value();
+        Some(self.id_tx_map.get(&id)?.value().bytes.clone())
+    }
 }
@@ -5,4 +5,10 @@ pub struct TxDepth {
     pub ancestor_count: usize,
     pub tx_id: Txid,
     pub bytes: Vec<u8>,
+    pub vsize: u64,
+    pub weight: u64,
+    pub fee_sat: u64,
+    /// txids of this transaction's direct in-mempool parents (the node's
+    /// `depends` field), used to walk ancestor packages for `/blocktemplate`.
+    pub depends: Vec<Txid>,
 }